@@ -7,6 +7,15 @@ use libtock_platform::{
     return_variant, syscall_class, DefaultConfig, ErrorCode, Register, ReturnVariant, Syscalls,
 };
 
+use core::cell::RefCell;
+use core::future::Future;
+use core::marker::{PhantomData, PhantomPinned};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+mod channel;
+pub use channel::IpcChannel;
+
 /// The IPC API provides ...
 
 pub struct Ipc<S: Syscalls, C: Config = DefaultConfig>(S, C);
@@ -133,6 +142,236 @@ impl<S: Syscalls, C: Config> Ipc<S, C> {
         }
         Ok(())
     }
+
+    /// Share a buffer with another process for as long as the returned
+    /// [`IpcShareHandle`] is held; the share is revoked when it is dropped.
+    ///
+    /// Unlike [`Ipc::share`], which takes `&'static mut [u8]` and never
+    /// revokes the allow, this accepts any scoped buffer (e.g. one on the
+    /// stack), making request/response patterns like "share a buffer,
+    /// notify, await the reply, drop" memory-safe without pinning a static
+    /// buffer for the life of the program.
+    pub fn share_scoped(svc_id: u32, buf: &mut [u8]) -> Result<IpcShareHandle<'_, S>, ErrorCode> {
+        let ptr = buf.as_ptr();
+        let len = buf.len();
+
+        // Safety: ptr and len are valid components of a slice.
+        let [r0, r1, _, _] = unsafe {
+            S::syscall4::<{ syscall_class::ALLOW_RW }>([
+                DRIVER_NUM.into(),
+                svc_id.into(),
+                ptr.into(),
+                len.into(),
+            ])
+        };
+        let rv: ReturnVariant = r0.as_u32().into();
+        if rv == return_variant::FAILURE_2_U32 {
+            // Safety: TRD 104 guarantees that if r0 is Failure with 2 U32,
+            // then r1 will contain a valid error code. ErrorCode is
+            // designed to be safely transmuted directly from a kernel error
+            // code.
+            return Err(unsafe { core::mem::transmute(r1.as_u32()) });
+        }
+        Ok(IpcShareHandle {
+            svc_id,
+            _syscalls: PhantomData,
+            _buf: PhantomData,
+        })
+    }
+
+    /// Returns a persistent subscription to `svc_id`'s IPC notifications.
+    ///
+    /// This subscribes through the same syscall as [`Ipc::register`], so it
+    /// works equally for a service awaiting `notify_service` from a client
+    /// or a client awaiting `notify_client` from a service; which one fires
+    /// depends only on which side calls `notify_service`/`notify_client`.
+    /// Unlike [`Ipc::register`], which requires a synchronous
+    /// [`IpcCallback`], [`IpcSubscription::wait`] is meant to be awaited
+    /// from an `async fn`/`Executor` task, e.g.:
+    ///
+    /// ```ignore
+    /// let mut sub = core::pin::pin!(Ipc::<S>::wait_notify(id));
+    /// loop {
+    ///     let (pid, buf) = sub.as_mut().wait().await?;
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// The subscription is established once, the first time `wait` is
+    /// polled, and stays registered with the kernel for as long as `sub`
+    /// lives — `wait`'s returned future only ever borrows it. Calling
+    /// [`Ipc::wait_notify`] fresh on every loop iteration instead (so each
+    /// iteration's future unsubscribes on drop before the next resubscribes)
+    /// would leave a window where a notification delivered between
+    /// iterations is silently lost.
+    pub fn wait_notify(svc_id: u32) -> IpcSubscription<S> {
+        IpcSubscription::new(svc_id)
+    }
+}
+
+/// A persistent subscription to an IPC service's notifications, returned by
+/// [`Ipc::wait_notify`]. Call [`wait`](IpcSubscription::wait) to get a
+/// future that resolves on the next notification; the underlying kernel
+/// subscription is established once (on the first `wait`) and stays
+/// registered across however many times `wait` is called, so no
+/// notification can be lost in a gap between re-subscribing.
+///
+/// Every field here is individually `Unpin`, but the kernel is handed this
+/// struct's address and relies on it staying fixed until `Drop`
+/// unsubscribes; without `_pin` the type would be `Unpin` too, `Pin` would
+/// guarantee nothing, and a safe caller could poll once (subscribing) and
+/// then move the subscription, leaving the kernel with a dangling pointer.
+pub struct IpcSubscription<S: Syscalls> {
+    svc_id: u32,
+    subscribed: bool,
+    waker: RefCell<Option<Waker>>,
+    result: RefCell<Option<Result<(u32, &'static mut [u8]), ErrorCode>>>,
+    _syscalls: PhantomData<S>,
+    _pin: PhantomPinned,
+}
+
+impl<S: Syscalls> IpcSubscription<S> {
+    fn new(svc_id: u32) -> Self {
+        Self {
+            svc_id,
+            subscribed: false,
+            waker: RefCell::new(None),
+            result: RefCell::new(None),
+            _syscalls: PhantomData,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Returns a future that resolves the next time this subscription's
+    /// service notifies this process, yielding the notifying process's id
+    /// and the buffer it shared (or the empty buffer if it shared none).
+    ///
+    /// Subscribes to the kernel the first time a future returned by `wait`
+    /// is polled across the lifetime of `self`; later futures reuse that
+    /// same registration.
+    pub fn wait(self: Pin<&mut Self>) -> IpcWait<'_, S> {
+        IpcWait { subscription: self }
+    }
+}
+
+impl<S: Syscalls> Drop for IpcSubscription<S> {
+    fn drop(&mut self) {
+        if self.subscribed {
+            unsafe {
+                // Safety: The null upcall pointer unsubscribes the
+                // previously registered upcall.
+                S::syscall4::<{ syscall_class::SUBSCRIBE }>([
+                    DRIVER_NUM.into(),
+                    self.svc_id.into(),
+                    0usize.into(),
+                    0usize.into(),
+                ])
+            };
+        }
+    }
+}
+
+/// A future returned by [`IpcSubscription::wait`] that resolves on the next
+/// notification delivered to the borrowed subscription.
+pub struct IpcWait<'a, S: Syscalls> {
+    subscription: Pin<&'a mut IpcSubscription<S>>,
+}
+
+impl<'a, S: Syscalls> Future for IpcWait<'a, S> {
+    type Output = Result<(u32, &'static mut [u8]), ErrorCode>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: we only ever reborrow `subscription`, never move out of
+        // it; its own pin guarantee is unaffected.
+        let subscription = unsafe { &mut self.as_mut().get_unchecked_mut().subscription };
+        let sub = unsafe { subscription.as_mut().get_unchecked_mut() };
+
+        if let Some(result) = sub.result.borrow_mut().take() {
+            return Poll::Ready(result);
+        }
+
+        *sub.waker.borrow_mut() = Some(cx.waker().clone());
+
+        if sub.subscribed {
+            return Poll::Pending;
+        }
+
+        // The upcall function passed to the Tock kernel.
+        //
+        // Safety: cbptr must be a reference to a valid, pinned instance of
+        // IpcSubscription<S>; Drop unsubscribes before that stops holding.
+        unsafe extern "C" fn kernel_upcall<S: Syscalls>(
+            target: u32,
+            len: u32,
+            ptr: Register,
+            data: Register,
+        ) {
+            let subscription: *const IpcSubscription<S> = data.into();
+            let subscription = unsafe { &*subscription };
+            let buf = unsafe {
+                if ptr.as_u32() != 0 {
+                    core::slice::from_raw_parts_mut(ptr.0 as *mut u8, len as usize)
+                } else {
+                    &mut EMPTY_BUF
+                }
+            };
+            *subscription.result.borrow_mut() = Some(Ok((target, buf)));
+            if let Some(waker) = subscription.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+
+        let upcall = kernel_upcall::<S> as *const ();
+        let cbptr = sub as *const IpcSubscription<S>;
+
+        // Safety: upcall is kernel_upcall::<S> and cbptr is a pointer to
+        // the subscription, which the caller keeps pinned for as long as
+        // it lives (enforced by `_pin` on `IpcSubscription`).
+        let [r0, r1, _, _] = unsafe {
+            S::syscall4::<{ syscall_class::SUBSCRIBE }>([
+                DRIVER_NUM.into(),
+                sub.svc_id.into(),
+                upcall.into(),
+                cbptr.into(),
+            ])
+        };
+
+        let rv: ReturnVariant = r0.as_u32().into();
+        if rv == return_variant::FAILURE_2_U32 {
+            // Safety: TRD 104 guarantees that if r0 is Failure with 2 U32,
+            // then r1 will contain a valid error code. ErrorCode is
+            // designed to be safely transmuted directly from a kernel error
+            // code.
+            return Poll::Ready(Err(unsafe { core::mem::transmute(r1.as_u32()) }));
+        }
+        sub.subscribed = true;
+        Poll::Pending
+    }
+}
+
+/// A buffer shared with another process via [`Ipc::share_scoped`]. The
+/// share is revoked (via an `ALLOW_RW` with a zero-length slice) when this
+/// handle is dropped, guaranteeing the peer can no longer touch the buffer
+/// once it leaves scope.
+pub struct IpcShareHandle<'a, S: Syscalls> {
+    svc_id: u32,
+    _syscalls: PhantomData<S>,
+    _buf: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a, S: Syscalls> Drop for IpcShareHandle<'a, S> {
+    fn drop(&mut self) {
+        unsafe {
+            // Safety: The zero-length slice unshares the previously shared
+            // buffer.
+            S::syscall4::<{ syscall_class::ALLOW_RW }>([
+                DRIVER_NUM.into(),
+                self.svc_id.into(),
+                0usize.into(),
+                0usize.into(),
+            ])
+        };
+    }
 }
 
 pub trait IpcCallback {
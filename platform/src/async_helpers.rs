@@ -6,7 +6,8 @@ use crate::{
 
 use core::future::Future;
 use core::pin::Pin;
-use core::task::{Context, Poll};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 
 /// A yield for async implementations.
 /// This doesn't actually issue the yield syscall, it yields to the async
@@ -24,10 +25,14 @@ impl Yield {
 impl Future for Yield {
     type Output = ();
 
-    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.ready {
             false => {
                 self.as_mut().ready = true;
+                // Wake ourselves immediately: nothing external is going to
+                // set our ready bit, so without this the executor would see
+                // no task ready to run and park forever in `yield_wait`.
+                cx.waker().wake_by_ref();
                 Poll::Pending
             }
             true => Poll::Ready(()),
@@ -35,10 +40,51 @@ impl Future for Yield {
     }
 }
 
-/// The simplest possible executor for async tasks.
-/// A proper executor would take sleeping tasks off of the run queue until
-/// they are woken up.  This executor simply loops over the list of tasks
-/// and polls them until they return `Ready` (meaning completed).
+/// The vtable shared by every per-task waker handed out by `Executor`.
+/// Waking a task only needs to set that task's bit in the shared ready mask,
+/// so wake/wake_by_ref boil down to an atomic OR, clone just copies the data
+/// pointer, and drop is a no-op (the waker's data lives as long as the
+/// `Executor::run` call that created it).
+static TASK_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    task_waker_clone,
+    task_waker_wake,
+    task_waker_wake_by_ref,
+    task_waker_drop,
+);
+
+/// What a per-task `RawWaker`'s data pointer actually points at: the ready
+/// mask shared with the executor, and the bit this task owns within it.
+struct TaskWakerData {
+    ready_mask: *const AtomicUsize,
+    bit: usize,
+}
+
+unsafe fn task_waker_clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &TASK_WAKER_VTABLE)
+}
+
+unsafe fn task_waker_wake(data: *const ()) {
+    // Safety: `wake` consumes a waker with the same data pointer `wake_by_ref`
+    // accepts, and we don't need to do anything different with ownership.
+    unsafe { task_waker_wake_by_ref(data) }
+}
+
+unsafe fn task_waker_wake_by_ref(data: *const ()) {
+    // Safety: `data` was created from a live `&TaskWakerData` in
+    // `Executor::run`, which outlives every waker handed out during the run.
+    let data = unsafe { &*(data as *const TaskWakerData) };
+    // Safety: `ready_mask` points at the `AtomicUsize` owned by the
+    // `Executor::run` call that is still on the stack below us.
+    unsafe { &*data.ready_mask }.fetch_or(data.bit, Ordering::Release);
+}
+
+unsafe fn task_waker_drop(_data: *const ()) {}
+
+/// An executor for async tasks that sleeps the process (via the blocking
+/// yield syscall) whenever no task can make progress, rather than busy
+/// looping.  Each task is given a waker that, when invoked, sets that task's
+/// bit in a shared ready mask; `run` only polls tasks whose bit is set, and
+/// blocks in the kernel when the mask goes to zero.
 pub struct Executor<'a, S: Syscalls, const TASKS: usize> {
     task: [Pin<&'a mut dyn Future<Output = ()>>; TASKS],
     done: [bool; TASKS],
@@ -47,6 +93,10 @@ pub struct Executor<'a, S: Syscalls, const TASKS: usize> {
 
 impl<'a, S: Syscalls, const TASKS: usize> Executor<'a, S, TASKS> {
     pub fn new(task: [Pin<&'a mut dyn Future<Output = ()>>; TASKS]) -> Executor<S, TASKS> {
+        debug_assert!(
+            TASKS <= usize::BITS as usize,
+            "TASKS must fit in the ready mask"
+        );
         Self {
             task,
             done: [false; TASKS],
@@ -54,45 +104,112 @@ impl<'a, S: Syscalls, const TASKS: usize> Executor<'a, S, TASKS> {
         }
     }
 
-    pub fn run(&mut self, cx: &mut Context<'_>) {
-        let mut all_done = 0;
-        while all_done != TASKS {
-            all_done = 0;
+    pub fn run(&mut self) {
+        // Every not-yet-done task starts out ready to be polled once. Tasks
+        // that are already done (run() may be called more than once, or
+        // re-entered with some tasks already complete) must *not* have
+        // their bit set, or the mask would never reach zero and `run` would
+        // busy-loop instead of reaching `yield_wait`.
+        let initial_mask = (0..TASKS).fold(0usize, |mask, i| {
+            if self.done[i] {
+                mask
+            } else {
+                mask | (1usize << i)
+            }
+        });
+        let ready_mask = AtomicUsize::new(initial_mask);
+        let waker_data: [TaskWakerData; TASKS] = core::array::from_fn(|i| TaskWakerData {
+            ready_mask: &ready_mask,
+            bit: 1usize << i,
+        });
+
+        let mut done_count = self.done.iter().filter(|&&done| done).count();
+        while done_count != TASKS {
+            let mask = ready_mask.load(Ordering::Acquire);
+            if mask == 0 {
+                // Nothing is ready to run; park the process until an upcall
+                // flips a bit in the ready mask.
+                S::yield_wait();
+                continue;
+            }
+
             for i in 0..TASKS {
+                if mask & waker_data[i].bit == 0 {
+                    continue;
+                }
                 if self.done[i] {
-                    all_done += 1;
-                } else {
-                    match self.task[i].as_mut().poll(cx) {
-                        Poll::Ready(_) => {
-                            self.done[i] = true;
-                            all_done += 1;
-                        }
-                        Poll::Pending => {
-                            // Nothing
-                        }
+                    // A stale wake on an already-completed task: clear it so
+                    // it can't keep the mask from ever reaching zero.
+                    ready_mask.fetch_and(!waker_data[i].bit, Ordering::AcqRel);
+                    continue;
+                }
+                // Clear the bit before polling: if the task immediately
+                // re-arms (e.g. it wakes itself), we want that wake to stick
+                // even though we're about to poll it right now.
+                ready_mask.fetch_and(!waker_data[i].bit, Ordering::AcqRel);
+
+                let raw_waker = RawWaker::new(
+                    &waker_data[i] as *const TaskWakerData as *const (),
+                    &TASK_WAKER_VTABLE,
+                );
+                // Safety: TASK_WAKER_VTABLE's functions satisfy the RawWaker
+                // contract (clone/wake/wake_by_ref/drop are all safe to call
+                // for the lifetime of waker_data, which outlives this poll).
+                let waker = unsafe { Waker::from_raw(raw_waker) };
+                let mut cx = Context::from_waker(&waker);
+
+                match self.task[i].as_mut().poll(&mut cx) {
+                    Poll::Ready(_) => {
+                        self.done[i] = true;
+                        done_count += 1;
+                    }
+                    Poll::Pending => {
+                        // Nothing; the task is responsible for waking itself
+                        // (or being woken by an upcall) when it can progress.
                     }
                 }
             }
-            S::yield_no_wait();
         }
     }
 }
 
+/// The buffer that occupied an allow slot before a [`Share`] was created,
+/// i.e. the `(ptr, len)` pair TRD 104 returns in r1/r2 on a successful
+/// allow. `(0, 0)` means the slot was previously empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreviousAllow {
+    pub ptr: usize,
+    pub len: usize,
+}
+
 /// Share provides an interface to the allow syscalls.  The allow is
-/// cancelled when the `Share` goes out of scope.
+/// cancelled (the slot is left empty) when the `Share` goes out of scope;
+/// [`Share::restore_previous`] re-installs the buffer that previously
+/// occupied the slot instead.
+///
+/// `data` is taken as `&'a [u8]` rather than `&'a mut [u8]` so that
+/// [`ShareUserspaceReadable`] can be built on top of this same type: for an
+/// ordinary read-write allow, touching the buffer while it is shared is
+/// undefined behavior, but `allow_userspace_readable` guarantees the app may
+/// keep reading the buffer while the driver holds it, so callers of that
+/// variant are expected to use the shared reference for inspection only.
 pub struct Share<'a, S: Syscalls, const SHARE_TYPE: usize> {
     driver_num: u32,
     buffer_num: u32,
+    previous: PreviousAllow,
     _syscalls: core::marker::PhantomData<S>,
     _data: core::marker::PhantomData<&'a [u8]>,
 }
 
 impl<'a, S: Syscalls, const SHARE_TYPE: usize> Share<'a, S, SHARE_TYPE> {
+    /// Performs the allow, returning the new `Share` along with whatever
+    /// buffer previously occupied this allow slot (per TRD 104, r1/r2 on a
+    /// successful allow carry the old `(ptr, len)`).
     pub fn new(
         driver_num: u32,
         buffer_num: u32,
         data: &'a [u8],
-    ) -> Result<Share<'a, S, SHARE_TYPE>, ErrorCode> {
+    ) -> Result<(Share<'a, S, SHARE_TYPE>, PreviousAllow), ErrorCode> {
         let ptr = data.as_ptr();
         let len = data.len();
 
@@ -114,12 +231,48 @@ impl<'a, S: Syscalls, const SHARE_TYPE: usize> Share<'a, S, SHARE_TYPE> {
             // code.
             return Err(unsafe { core::mem::transmute(r1.as_u32()) });
         }
-        Ok(Self {
-            driver_num,
-            buffer_num,
-            _syscalls: core::marker::PhantomData,
-            _data: core::marker::PhantomData,
-        })
+        // Per TRD 104, a successful allow returns the previous buffer's
+        // pointer and length in r1/r2.
+        let previous = PreviousAllow {
+            ptr: r1.as_u32() as usize,
+            len: r2.as_u32() as usize,
+        };
+        Ok((
+            Self {
+                driver_num,
+                buffer_num,
+                previous,
+                _syscalls: core::marker::PhantomData,
+                _data: core::marker::PhantomData,
+            },
+            previous,
+        ))
+    }
+
+    /// Consumes this `Share` and re-installs the buffer that occupied the
+    /// slot before it (as returned by `new`), instead of the default
+    /// zero-out-on-drop behavior.
+    ///
+    /// # Safety
+    ///
+    /// The `previous` buffer's owner must guarantee that buffer is still
+    /// valid for as long as the kernel keeps this new allow in place — in
+    /// practice, that means the `Share` being restored here must be dropped
+    /// (or itself restored) strictly after whatever `Share` originally
+    /// captured `previous`. Restoring a buffer whose owner has already
+    /// unshared or dropped it hands the kernel a dangling pointer.
+    pub unsafe fn restore_previous(self) {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe {
+            // Safety: see the function's Safety section; the caller has
+            // guaranteed `previous` is still a live allow.
+            S::syscall4::<{ SHARE_TYPE }>([
+                this.driver_num.into(),
+                this.buffer_num.into(),
+                this.previous.ptr.into(),
+                this.previous.len.into(),
+            ])
+        };
     }
 }
 
@@ -127,6 +280,13 @@ impl<'a, S: Syscalls, const SHARE_TYPE: usize> Drop for Share<'a, S, SHARE_TYPE>
     fn drop(&mut self) {
         unsafe {
             // Safety: The zero-slice unshares the previously shared buffer.
+            // We deliberately do not restore `self.previous` here: it
+            // belongs to some other registration whose lifetime isn't tied
+            // to this `Share`, so re-arming it unconditionally on drop could
+            // hand the kernel a dangling pointer if that registration has
+            // already been dropped (e.g. two `Share`s over the same slot
+            // dropped out of LIFO order). Callers who know the restore is
+            // sound should call `restore_previous` instead.
             S::syscall4::<{ SHARE_TYPE }>([
                 self.driver_num.into(),
                 self.buffer_num.into(),
@@ -139,22 +299,51 @@ impl<'a, S: Syscalls, const SHARE_TYPE: usize> Drop for Share<'a, S, SHARE_TYPE>
 
 type ShareRo<'a, S: Syscalls> = Share<'a, S, { syscall_class::ALLOW_RO }>;
 type ShareRw<'a, S: Syscalls> = Share<'a, S, { syscall_class::ALLOW_RW }>;
+/// A share over `allow_userspace_readable`: the driver gets read-write
+/// access to the buffer, but unlike [`ShareRw`] the app may keep reading it
+/// (through the same `&'a [u8]` passed to [`Share::new`]) for as long as the
+/// share is held.
+///
+/// `pub` (unlike [`ShareRo`]/[`ShareRw`]) so drivers outside this module —
+/// e.g. streaming ADC/audio capture — can actually name it. If this crate's
+/// root re-exports `async_helpers`'s public items, this type is reachable as
+/// `libtock_platform::ShareUserspaceReadable`; otherwise name it via this
+/// module's path.
+pub type ShareUserspaceReadable<'a, S: Syscalls> =
+    Share<'a, S, { syscall_class::ALLOW_USERSPACE_READABLE }>;
+
+/// The upcall that occupied a subscribe slot before a [`SubscribeUpcall`]
+/// was created, i.e. the function pointer and data pointer TRD 104 returns
+/// in r1/r2 on a successful subscribe. `(0, 0)` means the slot was
+/// previously unused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreviousUpcall {
+    pub func: usize,
+    pub data: usize,
+}
 
 /// SubscribeUpcall provides subscriptions to upcalls.  The subscription is
-/// cancelled when the `Share` goes out of scope.
+/// cancelled (the slot is left empty) when the `SubscribeUpcall` goes out
+/// of scope; [`SubscribeUpcall::restore_previous`] re-installs the upcall
+/// that previously occupied the slot instead.
 pub struct SubscribeUpcall<'a, S: Syscalls> {
     driver_num: u32,
     subscribe_num: u32,
+    previous: PreviousUpcall,
     _syscalls: core::marker::PhantomData<S>,
     _upcall: core::marker::PhantomData<&'a dyn Upcall<AnyId>>,
 }
 
 impl<'a, S: Syscalls> SubscribeUpcall<'a, S> {
+    /// Performs the subscribe, returning the new `SubscribeUpcall` along
+    /// with whatever upcall previously occupied this slot (per TRD 104,
+    /// r1/r2 on a successful subscribe carry the old function and data
+    /// pointers).
     pub fn new<U: Upcall<AnyId>>(
         driver_num: u32,
         subscribe_num: u32,
         upcall: &'a U,
-    ) -> Result<SubscribeUpcall<'a, S>, ErrorCode> {
+    ) -> Result<(SubscribeUpcall<'a, S>, PreviousUpcall), ErrorCode> {
         unsafe extern "C" fn kernel_upcall<S: Syscalls, U: Upcall<AnyId>>(
             arg0: u32,
             arg1: u32,
@@ -167,7 +356,7 @@ impl<'a, S: Syscalls> SubscribeUpcall<'a, S> {
 
         let kup_func = kernel_upcall::<S, U> as *const ();
         let kup_data = upcall as *const U;
-        let [r0, r1, _, _] = unsafe {
+        let [r0, r1, r2, _] = unsafe {
             S::syscall4::<{ syscall_class::SUBSCRIBE }>([
                 driver_num.into(),
                 subscribe_num.into(),
@@ -184,19 +373,64 @@ impl<'a, S: Syscalls> SubscribeUpcall<'a, S> {
             // code.
             return Err(unsafe { core::mem::transmute(r1.as_u32()) });
         }
-        Ok(Self {
-            driver_num,
-            subscribe_num,
-            _syscalls: core::marker::PhantomData,
-            _upcall: core::marker::PhantomData,
-        })
+        // Per TRD 104, a successful subscribe returns the previous upcall's
+        // function and data pointers in r1/r2.
+        let previous = PreviousUpcall {
+            func: r1.as_u32() as usize,
+            data: r2.as_u32() as usize,
+        };
+        Ok((
+            Self {
+                driver_num,
+                subscribe_num,
+                previous,
+                _syscalls: core::marker::PhantomData,
+                _upcall: core::marker::PhantomData,
+            },
+            previous,
+        ))
+    }
+
+    /// Consumes this `SubscribeUpcall` and re-installs the upcall that
+    /// occupied the slot before it (as returned by `new`), instead of the
+    /// default zero-out-on-drop behavior.
+    ///
+    /// # Safety
+    ///
+    /// The `previous` upcall's owner must guarantee that upcall is still
+    /// valid for as long as the kernel keeps this new subscription in
+    /// place — in practice, that means the `SubscribeUpcall` being restored
+    /// here must be dropped (or itself restored) strictly after whatever
+    /// `SubscribeUpcall` originally captured `previous`. Restoring an
+    /// upcall whose owner has already unsubscribed or dropped it hands the
+    /// kernel a dangling function/data pointer.
+    pub unsafe fn restore_previous(self) {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe {
+            // Safety: see the function's Safety section; the caller has
+            // guaranteed `previous` is still a live subscription.
+            S::syscall4::<{ syscall_class::SUBSCRIBE }>([
+                this.driver_num.into(),
+                this.subscribe_num.into(),
+                this.previous.func.into(),
+                this.previous.data.into(),
+            ])
+        };
     }
 }
 
 impl<'a, S: Syscalls> Drop for SubscribeUpcall<'a, S> {
     fn drop(&mut self) {
         unsafe {
-            // Safety: The null upcall pointer unsubscribes the previously registered upcall.
+            // Safety: The null upcall pointer unsubscribes the previously
+            // registered upcall. We deliberately do not restore
+            // `self.previous` here: it belongs to some other registration
+            // whose lifetime isn't tied to this `SubscribeUpcall`, so
+            // re-arming it unconditionally on drop could hand the kernel a
+            // dangling pointer if that registration has already been
+            // dropped (e.g. two subscriptions over the same slot dropped
+            // out of LIFO order). Callers who know the restore is sound
+            // should call `restore_previous` instead.
             S::syscall4::<{ syscall_class::SUBSCRIBE }>([
                 self.driver_num.into(),
                 self.subscribe_num.into(),
@@ -206,3 +440,29 @@ impl<'a, S: Syscalls> Drop for SubscribeUpcall<'a, S> {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtock_unittest::fake;
+
+    // A task that does nothing but cooperatively yield a fixed number of
+    // times before completing.
+    async fn yield_n_times(n: usize) {
+        for _ in 0..n {
+            Yield::now().await;
+        }
+    }
+
+    #[test]
+    fn executor_makes_progress_on_pure_yield_task() {
+        // Regression test: `Yield::poll` must wake its own task, or the
+        // executor sees an all-zero ready mask after the first poll and
+        // parks in `yield_wait` forever.
+        let _kernel = fake::Kernel::new();
+        let mut task = core::pin::pin!(yield_n_times(3));
+        let task: Pin<&mut dyn Future<Output = ()>> = task.as_mut();
+        let mut executor = Executor::<fake::Syscalls, 1>::new([task]);
+        executor.run();
+    }
+}